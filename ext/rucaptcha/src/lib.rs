@@ -1,6 +1,8 @@
 use magnus::{define_class, function, Error, Object};
 
 mod captcha;
+mod pow;
+mod token;
 
 pub fn create(
     len: usize,
@@ -20,13 +22,69 @@ pub fn create(
         .format(&format)
         .build();
 
-    (out.text, out.image)
+    (out.text.expect("create() never enables signed tokens"), out.image)
+}
+
+/// Like `create`, but returns a stateless HMAC-signed token instead of the
+/// plaintext answer, so the Ruby side can verify a response without caching
+/// it server-side. See `verify`.
+pub fn create_signed(
+    len: usize,
+    difficulty: usize,
+    line: bool,
+    noise: bool,
+    circle: bool,
+    format: String,
+    key: String,
+    ttl_secs: u64,
+) -> (String, Vec<u8>) {
+    let c = captcha::CaptchaBuilder::new();
+    let out = c
+        .complexity(difficulty)
+        .length(len)
+        .line(line)
+        .noise(noise)
+        .circle(circle)
+        .format(&format)
+        .signed(key.as_bytes(), ttl_secs)
+        .build();
+
+    (
+        out.token.expect("signed() always produces a token"),
+        out.image,
+    )
+}
+
+/// Check a token returned by `create_signed` against `user_input`.
+pub fn verify(token: String, user_input: String, key: String) -> bool {
+    token::verify(&token, &user_input, key.as_bytes())
+}
+
+// magnus's `function!` requires every argument to implement `TryConvert`,
+// which borrowed `&str` does not, so these take owned `String`s (same
+// convention as `create`'s `format` argument) and delegate to `pow`.
+pub fn prove_work(salt: String, phrase: String, difficulty: u32) -> (u64, String) {
+    pow::prove_work(&salt, &phrase, difficulty)
+}
+
+pub fn is_valid_proof(
+    nonce: u64,
+    salt: String,
+    phrase: String,
+    difficulty: u32,
+    result: String,
+) -> bool {
+    pow::is_valid_proof(nonce, &salt, &phrase, difficulty, &result)
 }
 
 #[magnus::init]
 fn init() -> Result<(), Error> {
     let class = define_class("RuCaptchaCore", magnus::class::object())?;
     class.define_singleton_method("create", function!(create, 6))?;
+    class.define_singleton_method("create_signed", function!(create_signed, 8))?;
+    class.define_singleton_method("verify", function!(verify, 3))?;
+    class.define_singleton_method("prove_work", function!(prove_work, 3))?;
+    class.define_singleton_method("is_valid_proof", function!(is_valid_proof, 5))?;
 
     Ok(())
 }
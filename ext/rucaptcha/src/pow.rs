@@ -0,0 +1,66 @@
+//! Proof-of-work companion challenge, borrowed from the mCaptcha model: a
+//! client must burn CPU time finding a `nonce` before an answer is accepted,
+//! which slows bulk automated solving without requiring any server-side
+//! session state.
+
+use sha2::{Digest, Sha256};
+
+fn hash_attempt(salt: &str, phrase: &str, nonce: u64) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(phrase.as_bytes());
+    hasher.update(nonce.to_le_bytes());
+    hasher.finalize().into()
+}
+
+fn leading_u128(hash: &[u8; 32]) -> u128 {
+    let mut buf = [0u8; 16];
+    buf.copy_from_slice(&hash[..16]);
+    u128::from_be_bytes(buf)
+}
+
+fn meets_difficulty(hash: &[u8; 32], difficulty: u32) -> bool {
+    leading_u128(hash).checked_mul(difficulty as u128).is_some()
+}
+
+/// Search for the first `nonce` whose hash satisfies `difficulty`, returning
+/// `(nonce, hex_result)`.
+pub fn prove_work(salt: &str, phrase: &str, difficulty: u32) -> (u64, String) {
+    let mut nonce: u64 = 0;
+    loop {
+        let hash = hash_attempt(salt, phrase, nonce);
+        if meets_difficulty(&hash, difficulty) {
+            return (nonce, hex::encode(hash));
+        }
+        nonce += 1;
+    }
+}
+
+/// Verify a claimed `(nonce, result)` pair against `difficulty`.
+pub fn is_valid_proof(nonce: u64, salt: &str, phrase: &str, difficulty: u32, result: &str) -> bool {
+    let hash = hash_attempt(salt, phrase, nonce);
+    meets_difficulty(&hash, difficulty) && hex::encode(hash) == result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prove_work_round_trips() {
+        let (nonce, result) = prove_work("somesalt", "ABCD", 4);
+        assert!(is_valid_proof(nonce, "somesalt", "ABCD", 4, &result));
+    }
+
+    #[test]
+    fn test_wrong_nonce_rejected() {
+        let (nonce, result) = prove_work("somesalt", "ABCD", 4);
+        assert!(!is_valid_proof(nonce + 1, "somesalt", "ABCD", 4, &result));
+    }
+
+    #[test]
+    fn test_wrong_phrase_rejected() {
+        let (nonce, result) = prove_work("somesalt", "ABCD", 4);
+        assert!(!is_valid_proof(nonce, "somesalt", "WXYZ", 4, &result));
+    }
+}
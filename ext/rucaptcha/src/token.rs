@@ -0,0 +1,92 @@
+//! Stateless HMAC-signed verification tokens.
+//!
+//! Instead of the server persisting the plaintext answer somewhere (Redis,
+//! a session store, ...), `sign` bakes the answer and an expiry into a
+//! compact, tamper-evident token that the client round-trips back for
+//! `verify` to check. No server-side storage is needed.
+
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn now_unix() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is before the unix epoch")
+        .as_secs()
+}
+
+fn mac_over(key: &[u8], normalized_text: &str, expiry: u64) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts a key of any size");
+    mac.update(normalized_text.as_bytes());
+    mac.update(&expiry.to_be_bytes());
+    mac
+}
+
+/// Sign `text` so it expires `ttl_secs` from now: `base64url(expiry_u64 ||
+/// HMAC_SHA256(key, lowercase(text) || expiry))`.
+pub(crate) fn sign(text: &str, key: &[u8], ttl_secs: u64) -> String {
+    let expiry = now_unix() + ttl_secs;
+    let sig = mac_over(key, &text.to_lowercase(), expiry).finalize().into_bytes();
+
+    let mut payload = Vec::with_capacity(8 + sig.len());
+    payload.extend_from_slice(&expiry.to_be_bytes());
+    payload.extend_from_slice(&sig);
+
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(payload)
+}
+
+/// Verify `token` against `user_input`, rejecting expired tokens and
+/// comparing the HMAC in constant time.
+pub fn verify(token: &str, user_input: &str, key: &[u8]) -> bool {
+    let Ok(payload) = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(token) else {
+        return false;
+    };
+    if payload.len() <= 8 {
+        return false;
+    }
+
+    let (expiry_bytes, sig) = payload.split_at(8);
+    let expiry = u64::from_be_bytes(expiry_bytes.try_into().unwrap());
+    if now_unix() > expiry {
+        return false;
+    }
+
+    mac_over(key, &user_input.to_lowercase(), expiry)
+        .verify_slice(sig)
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let token = sign("AB12", b"secret", 60);
+        assert!(verify(&token, "ab12", b"secret"));
+        assert!(verify(&token, "AB12", b"secret"));
+    }
+
+    #[test]
+    fn test_wrong_answer_rejected() {
+        let token = sign("AB12", b"secret", 60);
+        assert!(!verify(&token, "ZZ99", b"secret"));
+    }
+
+    #[test]
+    fn test_wrong_key_rejected() {
+        let token = sign("AB12", b"secret", 60);
+        assert!(!verify(&token, "AB12", b"other-secret"));
+    }
+
+    #[test]
+    fn test_expired_token_rejected() {
+        let token = sign("AB12", b"secret", 0);
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        assert!(!verify(&token, "AB12", b"secret"));
+    }
+}